@@ -1,7 +1,10 @@
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::env;
 use std::error::Error;
 use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
 use std::process::Command;
 
 use clap::Parser;
@@ -12,10 +15,270 @@ use git2::Repository;
 use git2::RepositoryState;
 
 use repositories::discover;
+use repositories::format_known_repository_line;
 use repositories::load_known_repositories;
+use repositories::RepositoryKind;
 
+use pool::run_pooled;
+
+mod manifest;
+mod pool;
 mod repositories;
 
+/// Opens `repository_path` the way its `kind` demands: bare repositories
+/// via `Repository::open_bare` (no working tree to discover), everything
+/// else via the regular, working-tree-aware `Repository::open`.
+fn open_repository( repository_path: &str, kind: RepositoryKind ) -> Result<Repository,git2::Error> {
+    match kind {
+        RepositoryKind::Bare => Repository::open_bare( repository_path ),
+        RepositoryKind::WorkingTree | RepositoryKind::Linked => Repository::open( repository_path ),
+    }
+}
+
+/// Opens `repository_path`, fetches every remote (falling back to a `git`
+/// subprocess for remotes `git2` can't authenticate) and returns the full
+/// report as a single block of text, ready to be printed once it arrives.
+fn fetch_repository( ( repository_path, kind ): ( String, RepositoryKind ) ) -> String {
+
+    let mut report = format!( "Fetching \"{}\" ... \n", repository_path );
+
+    let repository = match open_repository( &repository_path, kind ) {
+        Ok( repository ) => repository,
+        Err( error ) => {
+            report.push_str( &format!( "Failed to open repository: {error}\n" ) );
+            return report;
+        },
+    };
+
+    let branches: Vec<String> =
+        match repository.branches( Some( BranchType::Local ) ) {
+            Ok( branches ) => branches.into_iter().filter_map(
+                | branch |
+                match branch {
+                    Ok( branch ) => Some( branch.0.name().unwrap().unwrap().to_owned() ),
+                    Err( _ ) => None,
+                }
+            ).collect(),
+            Err( error ) => {
+                report.push_str( &format!( "Failed to enumerate branches: {error}\n" ) );
+                return report;
+            },
+        };
+    report.push_str( &fetch_remotes( &repository, &repository_path, &branches ).text );
+
+    report
+}
+
+/// The outcome of fetching every remote of a repository: the human-readable
+/// report lines, plus whether every remote actually succeeded, so callers
+/// can branch on outcome without parsing the report's wording.
+struct FetchOutcome {
+    text: String,
+    success: bool,
+}
+
+/// Fetches every remote of `repository`, falling back to a `git` subprocess
+/// for remotes `git2` can't authenticate, and returns the report lines
+/// describing the outcome of each alongside whether all of them succeeded.
+fn fetch_remotes( repository: &Repository, repository_path: &str, branches: &[ String ] ) -> FetchOutcome {
+
+    let mut report = String::new();
+    let mut success = true;
+
+    let remotes: Vec<String> =
+        match repository.remotes() {
+            Ok( remotes ) => remotes.into_iter().filter_map(
+                | remote |
+                match remote {
+                    Some( remote ) => Some( remote.to_owned() ),
+                    None => None,
+                }
+            ).collect(),
+            Err( error ) => {
+                report.push_str( &format!( "Failed to enumerate remotes: {error}\n" ) );
+                return FetchOutcome { text: report, success: false };
+            },
+        };
+
+    for remote_str in &remotes {
+        if remotes.len() > 1 {
+            report.push_str( &format!( "(from remote {remote_str})\n" ) );
+        }
+        let Ok( mut remote ) = repository.find_remote( remote_str ) else {
+            report.push_str( "Failed to look up remote!\n" );
+            success = false;
+            continue;
+        };
+
+        let fetch_result = remote.fetch( branches, None, None );
+        if let Err( _ ) = fetch_result {
+            report.push_str( "(Trying authented fetch via a git subprocess ...) \n");
+            let output_result =
+                Command::new( "git" )
+                    .args( [ "fetch", remote_str ] )
+                    .current_dir( repository_path )
+                    .output();
+            let Ok( output ) = output_result else {
+                report.push_str( "Failed to call on git!\n" );
+                success = false;
+                continue;
+            };
+            if !output.status.success() {
+                report.push_str( "Failed!\n" );
+                report.push_str( &format!( "{:?}\n", output ) );
+                success = false;
+            } else {
+                report.push_str( "Succeeded.\n" );
+            }
+        } else {
+            report.push_str( "Succeeded.\n" );
+        }
+    }
+
+    FetchOutcome { text: report, success }
+}
+
+/// Opens `repository_path` and returns a report covering uncommitted
+/// changes, mid-operation state, and the ahead/behind position of every
+/// local branch relative to its upstream, or `None` if there's nothing
+/// noteworthy to report. Pass `fetch` to fetch first, so the ahead/behind
+/// counts reflect the true remote state rather than a stale local view.
+fn status_repository( repository_path: String, kind: RepositoryKind, verbose: bool, fetch: bool ) -> Option<String> {
+
+    if verbose {
+        println!( "Obtaining status of repository: {repository_path} ..." );
+    }
+
+    let repository = open_repository( &repository_path, kind ).ok()?;
+
+    let fetch_report = if fetch {
+        let branches: Vec<String> =
+            repository.branches( Some( BranchType::Local ) ).ok()?.into_iter().filter_map(
+                | branch |
+                match branch {
+                    Ok( branch ) => Some( branch.0.name().unwrap().unwrap().to_owned() ),
+                    Err( _ ) => None,
+                }
+            ).collect();
+        let outcome = fetch_remotes( &repository, &repository_path, &branches );
+        if outcome.success { None } else { Some( outcome.text ) }
+    } else {
+        None
+    };
+
+    // Bare repositories have no working tree, so `state()`/`statuses()`
+    // don't apply to them; only report their branches' sync state.
+    let unclean_files = if repository.is_bare() {
+        None
+    } else {
+        let state_clean = match repository.state() {
+            RepositoryState::Clean => true,
+            _ => false,
+        };
+        let status_clean = repository.statuses( None ).ok()?.iter()
+            .filter(
+                | status |
+                !status.status().is_ignored()
+            )
+            .count();
+        Some( ( state_clean, status_clean ) )
+    };
+
+    let branch_report_lines = branch_ahead_behind_lines( &repository );
+
+    let has_unclean_files = match unclean_files {
+        Some( ( state_clean, status_clean ) ) => !state_clean || status_clean != 0,
+        None => false,
+    };
+
+    if has_unclean_files || !branch_report_lines.is_empty() || fetch_report.is_some() {
+        let mut message = match unclean_files {
+            Some( ( _, status_clean ) ) =>
+                format!( "{status_clean} file(s) unclean @ {repository_path}" ),
+            None =>
+                format!( "(bare) @ {repository_path}" ),
+        };
+        for line in &branch_report_lines {
+            message.push_str( "\n  " );
+            message.push_str( line );
+        }
+        if let Some( fetch_report ) = fetch_report {
+            message.push_str( "\n  Fetch failed:\n" );
+            for line in fetch_report.lines() {
+                message.push_str( "    " );
+                message.push_str( line );
+                message.push( '\n' );
+            }
+        }
+        Some( message )
+    } else {
+        None
+    }
+}
+
+/// Walks upward from `working_directory` looking for an enclosing `.git`,
+/// returning the path of the repository it belongs to if found. The
+/// working directory itself doesn't count — only a `.git` in one of its
+/// ancestors, meaning we're nested inside someone else's checkout.
+fn enclosing_repository( working_directory: &Path ) -> Option<PathBuf> {
+
+    let mut current = working_directory.parent();
+
+    while let Some( directory ) = current {
+        if directory.join( ".git" ).exists() {
+            return Some( directory.to_owned() );
+        }
+        current = directory.parent();
+    }
+
+    None
+}
+
+/// Computes, for each local branch with a configured upstream, the
+/// ahead/behind commit counts relative to that upstream; branches with no
+/// upstream are flagged separately. Branches already in sync with their
+/// upstream are omitted.
+fn branch_ahead_behind_lines( repository: &Repository ) -> Vec<String> {
+
+    let Ok( branches ) = repository.branches( Some( BranchType::Local ) ) else {
+        return Vec::new();
+    };
+
+    let mut lines = Vec::new();
+
+    for branch in branches.filter_map( Result::ok ) {
+
+        let ( branch, _ ) = branch;
+
+        let Ok( Some( branch_name ) ) = branch.name() else {
+            continue;
+        };
+
+        match branch.upstream() {
+            Ok( upstream ) => {
+                let ( Some( local_oid ), Some( upstream_oid ) ) =
+                    ( branch.get().target(), upstream.get().target() ) else {
+                    continue;
+                };
+                let Ok( ( ahead, behind ) ) = repository.graph_ahead_behind( local_oid, upstream_oid ) else {
+                    continue;
+                };
+                if ahead != 0 || behind != 0 {
+                    let upstream_name = upstream.name().ok().flatten().unwrap_or( "?" ).to_owned();
+                    lines.push( format!(
+                        "{ahead} ahead, {behind} behind on '{branch_name}' (tracks {upstream_name})"
+                    ) );
+                }
+            },
+            Err( _ ) => {
+                lines.push( format!( "no upstream: {branch_name}" ) );
+            },
+        }
+    }
+
+    lines
+}
+
 #[ derive( Parser ) ]
 #[ command( author, version, about, long_about = None ) ]
 struct Cli {
@@ -38,15 +301,35 @@ enum Commands {
         prune: bool,
     },
     /// Print the status of each repository.
-    Status,
+    Status {
+        /// Maximum number of repositories to check concurrently.
+        #[ arg( short, long, default_value_t = 4 ) ]
+        jobs: usize,
+        /// Fetch each repository first, so ahead/behind counts reflect the
+        /// true remote state rather than a stale local view.
+        #[ arg( short, long ) ]
+        fetch: bool,
+    },
     /// Fetch each repository.
-    Fetch,
+    Fetch {
+        /// Maximum number of repositories to fetch concurrently.
+        #[ arg( short, long, default_value_t = 4 ) ]
+        jobs: usize,
+    },
     /// List all known repositories.
     List{
         /// Don't restrict to repositories of the current working directory.
         #[ arg( short, long ) ]
         global: bool
     },
+    /// Clone declared repositories that are missing on disk and report
+    /// repositories present on disk but not declared in the manifest.
+    Sync {
+        /// Path to the TOML manifest. Defaults to `reposcan.toml` in the
+        /// working directory.
+        #[ arg( short, long ) ]
+        manifest: Option<PathBuf>,
+    },
 }
 
 fn main() -> Result<(),Box<dyn Error>> {
@@ -59,8 +342,22 @@ fn main() -> Result<(),Box<dyn Error>> {
         panic!( "Failed to obtain string representation of the working directory!" )
     };
 
-    // TODO: Test whether we are in a subdirectory of a git repository. This
-    // should be reported to the user as an error.
+    if let Some( enclosing_repository ) = enclosing_repository( &working_directory ) {
+
+        let message = format!(
+            "You are inside repository \"{}\"; path-prefix scoping against the current working directory may behave unexpectedly.",
+            enclosing_repository.display(),
+        );
+
+        match &cli.mode {
+            Commands::Discover { .. } => {
+                return Err( message.into() );
+            },
+            _ => {
+                eprintln!( "Warning: {message}" );
+            },
+        }
+    }
 
     let Some( home_directory ) = home::home_dir() else {
         panic!( "Failed to obtain the user's home directory!" )
@@ -68,10 +365,11 @@ fn main() -> Result<(),Box<dyn Error>> {
 
     let repositories_file = home_directory.join( ".reposcanconfig" );
 
-    let mut all_known_repositories = load_known_repositories( &repositories_file )?;
+    let mut all_known_repositories: BTreeMap<String,RepositoryKind> =
+        load_known_repositories( &repositories_file )?;
 
     let repositories_in_working_directory: BTreeSet<String> =
-        all_known_repositories.iter()
+        all_known_repositories.keys()
             .filter_map(
                 | repository |
                 if repository.starts_with( working_directory_string ) {
@@ -90,18 +388,20 @@ fn main() -> Result<(),Box<dyn Error>> {
             prune,
         } => {
             
-            let discovered_repositories: Vec<String> =
+            let discovered_repositories: Vec<( String,RepositoryKind )> =
                 discover( &working_directory, cli.verbose )?.into_iter().map(
                     | repository | {
-                        repository.to_str().unwrap().to_owned()
+                        ( repository.path.to_str().unwrap().to_owned(), repository.kind )
                     }
                 ).collect();
+            let discovered_paths: Vec<String> =
+                discovered_repositories.iter().map( | ( path, _ ) | path.clone() ).collect();
 
-            let new_repositories: Vec<String> = discovered_repositories.iter()
+            let new_repositories: Vec<( String,RepositoryKind )> = discovered_repositories.iter()
                 .filter_map(
-                    | repository |
+                    | ( repository, kind ) |
                     if !repositories_in_working_directory.contains( repository ) {
-                        Some( repository.clone() )
+                        Some( ( repository.clone(), *kind ) )
                     } else {
                         None
                     }
@@ -109,7 +409,7 @@ fn main() -> Result<(),Box<dyn Error>> {
             let obsolete_repositories: Vec<String> = repositories_in_working_directory.iter()
                 .filter_map(
                     | repository |
-                    if !discovered_repositories.contains( repository ) {
+                    if !discovered_paths.contains( repository ) {
                         Some( repository.clone() )
                     } else {
                         None
@@ -118,9 +418,9 @@ fn main() -> Result<(),Box<dyn Error>> {
                 .collect();
 
             if *add {
-                for new_repository in &new_repositories {
-                    if !all_known_repositories.contains( new_repository ) {
-                        all_known_repositories.insert( new_repository.clone() );
+                for ( new_repository, kind ) in &new_repositories {
+                    if !all_known_repositories.contains_key( new_repository ) {
+                        all_known_repositories.insert( new_repository.clone(), *kind );
                         println!( "Added new repository: \"{}\"", new_repository );
                     }
                 }
@@ -137,7 +437,7 @@ fn main() -> Result<(),Box<dyn Error>> {
 
                 println!( "NEW repositories:" );
                 new_repositories.iter().for_each(
-                    | new_repository |
+                    | ( new_repository, _ ) |
                     println!( "{new_repository}" )
                 );
                 println!();
@@ -150,112 +450,97 @@ fn main() -> Result<(),Box<dyn Error>> {
                 println!();
             }
         },
-        Commands::Fetch => {
+        Commands::Fetch { jobs } => {
 
-            for repository_path in &all_known_repositories {
+            let repository_paths: Vec<( String,RepositoryKind )> =
+                all_known_repositories.iter().map( | ( path, kind ) | ( path.clone(), *kind ) ).collect();
 
-                let repository = Repository::open( repository_path )?;
-                println!(
-                    "Fetching \"{}\" ... ",
-                    repository_path
-                );
-                let branches: Vec<String> =
-                    repository.branches( Some( BranchType::Local ) )?.into_iter().filter_map(
-                        | branch |
-                        match branch {
-                            Ok( branch ) => Some( branch.0.name().unwrap().unwrap().to_owned() ),
-                            Err( _ ) => None,
-                        }
-                    ).collect();
-                let remotes: Vec<String> =
-                    repository.remotes()?.into_iter().filter_map(
-                        | remote |
-                        match remote {
-                            Some( remote ) => Some( remote.to_owned() ),
-                            None => None,
-                        }
-                    ).collect();
-                
-                for remote_str in &remotes {
-                    if remotes.len() > 1 {
-                        println!( "(from remote {remote_str})" );
-                    }
-                    let mut remote = repository.find_remote( remote_str )?;
-
-                    let fetch_result =
-                        remote.fetch( &branches, None, None );
-                    if let Err( _ ) = fetch_result {
-                        println!( "(Trying authented fetch via a git subprocess ...) ");
-                        let output_result =
-                            Command::new( "git" )
-                                .args( [ "fetch", remote_str ] )
-                                .current_dir( repository_path )
-                                .output();
-                        let Ok( output ) = output_result else {
-                            println!( "Failed to call on git!" );
-                            continue;
-                        };
-                        if !output.status.success() {
-                            println!( "Failed!" );
-                            println!( "{:?}", output );
-                        } else {
-                            println!(
-                                "Succeeded."
-                            );
-                        }
-                    } else {
-                        println!( "Succeeded.");
-                    }
+            let results = run_pooled( repository_paths, *jobs, fetch_repository );
+            for message in results {
+                println!( "{message}" );
+            }
+        },
+        Commands::Status { jobs, fetch } => {
+
+            let repository_paths: Vec<( String,RepositoryKind )> =
+                all_known_repositories.iter().map( | ( path, kind ) | ( path.clone(), *kind ) ).collect();
+            let verbose = cli.verbose;
+            let fetch = *fetch;
+
+            let results = run_pooled(
+                repository_paths,
+                *jobs,
+                move | ( repository_path, kind ) | status_repository( repository_path, kind, verbose, fetch )
+            );
+            for message in results.into_iter().flatten() {
+                println!( "{message}" );
+            }
+        },
+        Commands::List { global } => {
+            if *global {
+                for repository in all_known_repositories.keys() {
+                    println!( "{repository}" );
+                }
+            } else {
+                for repository in &repositories_in_working_directory {
+                    println!( "{repository}" );
                 }
-                println!();
             }
         },
-        Commands::Status => {
-            for repository_path in &all_known_repositories {
-                
-                if cli.verbose {
-                    println!( "Obtaining status of repository: {repository_path} ..." );
+        Commands::Sync { manifest } => {
+
+            let manifest_path =
+                manifest.clone().unwrap_or_else( || working_directory.join( "reposcan.toml" ) );
+
+            let declared_repositories = manifest::load_manifest( &manifest_path )?;
+
+            let mut declared_destinations: BTreeSet<String> = BTreeSet::new();
+
+            for declared_repository in &declared_repositories {
+
+                // Manifests naturally declare destinations as relative
+                // paths for portability, but known/discovered repositories
+                // are always tracked as absolute paths — resolve against
+                // the working directory before comparing or checking.
+                let absolute_destination =
+                    if declared_repository.destination.is_absolute() {
+                        declared_repository.destination.clone()
+                    } else {
+                        working_directory.join( &declared_repository.destination )
+                    };
+
+                if let Some( destination ) = absolute_destination.to_str() {
+                    declared_destinations.insert( destination.to_owned() );
                 }
 
-                let repository = Repository::open( repository_path )?;
-                let state_clean = match repository.state() {
-                    RepositoryState::Clean => true,
-                    _ => false,
-                };
-                let status_clean = repository.statuses( None )?.iter()
-                    .filter(
-                        | status |
-                        !status.status().is_ignored()
-                    )
-                    .count();
-
-                if !state_clean || status_clean != 0 {
-                    println!(
-                        "{} file(s) unclean @ {}",
-                        status_clean,
-                        repository_path,
-                    );
+                if absolute_destination.is_dir() {
+                    continue;
                 }
+
+                println!( "{}", manifest::clone_repository( declared_repository ) );
             }
-        },
-        Commands::List { global } => {
-            let repositories_to_display =
-                if *global {
-                    &all_known_repositories
-                } else {
-                    &repositories_in_working_directory
-                };
-            for repository in repositories_to_display {
-                println!( "{repository}" );
+
+            // Report repositories actually present on disk (not merely
+            // cached in `~/.reposcanconfig`) that the manifest doesn't
+            // declare.
+            let repositories_on_disk: Vec<String> =
+                discover( &working_directory, cli.verbose )?.into_iter()
+                    .filter_map( | repository | repository.path.to_str().map( str::to_owned ) )
+                    .collect();
+
+            for repository_path in &repositories_on_disk {
+                if !declared_destinations.contains( repository_path ) {
+                    println!( "(unmanaged: \"{}\")", repository_path );
+                }
             }
-        }
+        },
     }
 
     if let Commands::Discover { add, prune } = &cli.mode {
         if *add || *prune {
             let mut repositories_content = String::new();
-            for repository in all_known_repositories {
-                repositories_content.push_str( &repository );
+            for ( repository, kind ) in all_known_repositories {
+                repositories_content.push_str( &format_known_repository_line( &repository, kind ) );
                 repositories_content.push( '\n' );
             }
             fs::write( &repositories_file, repositories_content )?;
@@ -0,0 +1,93 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use git2::Repository;
+
+/// How a declared repository's remote should be reached. `Ssh` remotes are
+/// cloned via a `git` subprocess straight away, mirroring the authenticated
+/// fallback used for `Fetch`, since `git2` has no credential callbacks wired
+/// up for them.
+#[ derive( Deserialize ) ]
+#[ serde( rename_all = "lowercase" ) ]
+pub enum RemoteType {
+    Ssh,
+    Https,
+    File,
+}
+
+/// One `[[repository]]` entry of a manifest, describing a repository that
+/// should exist at `destination` once the manifest is synced.
+#[ derive( Deserialize ) ]
+pub struct ManifestRepository {
+    pub remote: String,
+    pub destination: PathBuf,
+    #[ serde( rename = "type" ) ]
+    pub remote_type: RemoteType,
+}
+
+#[ derive( Deserialize ) ]
+struct Manifest {
+    #[ serde( default ) ]
+    repository: Vec<ManifestRepository>,
+}
+
+#[ derive( Debug ) ]
+pub struct ManifestError( String );
+
+impl fmt::Display for ManifestError {
+    fn fmt( &self, formatter: &mut fmt::Formatter<'_> ) -> fmt::Result {
+        write!( formatter, "{}", self.0 )
+    }
+}
+
+impl Error for ManifestError {}
+
+/// Reads and parses a TOML manifest describing the desired repositories.
+pub fn load_manifest( manifest_path: &Path ) -> Result<Vec<ManifestRepository>,Box<dyn Error>> {
+
+    let manifest_content = fs::read_to_string( manifest_path )
+        .map_err( | error | ManifestError( format!( "Failed to read {manifest_path:?}: {error}" ) ) )?;
+
+    let manifest: Manifest = toml::from_str( &manifest_content )
+        .map_err( | error | ManifestError( format!( "Failed to parse {manifest_path:?}: {error}" ) ) )?;
+
+    Ok( manifest.repository )
+}
+
+/// Clones `entry` to its declared destination, falling back to a `git`
+/// subprocess when `git2` can't authenticate (or never stood a chance to,
+/// for `ssh` remotes), and returns a one-line report ready to be printed.
+pub fn clone_repository( entry: &ManifestRepository ) -> String {
+
+    if let RemoteType::Ssh = entry.remote_type {
+        return clone_via_subprocess( entry );
+    }
+
+    match Repository::clone( &entry.remote, &entry.destination ) {
+        Ok( _ ) => format!( "Cloned \"{}\" -> {:?}", entry.remote, entry.destination ),
+        Err( _ ) => clone_via_subprocess( entry ),
+    }
+}
+
+fn clone_via_subprocess( entry: &ManifestRepository ) -> String {
+
+    let output_result =
+        Command::new( "git" )
+            .args( [ "clone", &entry.remote, &entry.destination.to_string_lossy() ] )
+            .output();
+
+    match output_result {
+        Ok( output ) if output.status.success() =>
+            format!( "Cloned \"{}\" -> {:?} (via git subprocess)", entry.remote, entry.destination ),
+        Ok( output ) =>
+            format!( "Failed to clone \"{}\": {:?}", entry.remote, output ),
+        Err( error ) =>
+            format!( "Failed to call on git: {error}" ),
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+
+/// Runs `work` once for every item in `items` across a bounded pool of
+/// `jobs` worker threads, streaming each result back through the returned
+/// receiver as soon as it is produced.
+///
+/// Results arrive in completion order, not dispatch order, so a slow item
+/// never stalls the ones that finish sooner. `jobs` is clamped to at least
+/// one so a misconfigured pool still makes progress.
+pub fn run_pooled<T, R, F>( items: Vec<T>, jobs: usize, work: F ) -> mpsc::Receiver<R>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn( T ) -> R + Send + Sync + 'static,
+{
+    let jobs = jobs.max( 1 );
+
+    let ( result_sender, result_receiver ) = mpsc::channel();
+    let work = Arc::new( work );
+    let remaining_items = Arc::new( Mutex::new( items.into_iter() ) );
+
+    for _ in 0..jobs {
+
+        let remaining_items = Arc::clone( &remaining_items );
+        let work = Arc::clone( &work );
+        let result_sender = result_sender.clone();
+
+        thread::spawn( move || {
+            loop {
+                let next_item = remaining_items.lock().unwrap().next();
+                let Some( item ) = next_item else {
+                    break;
+                };
+                if result_sender.send( work( item ) ).is_err() {
+                    break;
+                }
+            }
+        } );
+    }
+
+    result_receiver
+}
@@ -1,17 +1,239 @@
-use std::collections::BTreeSet;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 
-pub fn discover( working_directory: &Path, verbose: bool ) -> Result<Vec<PathBuf>,std::io::Error> {
+/// A single compiled line from a `.reposcanignore` file, anchored to the
+/// directory it was found in so nested directories can still be matched
+/// against it.
+struct IgnorePattern {
+    origin: PathBuf,
+    negate: bool,
+    dir_only: bool,
+    anchored: bool,
+    segments: Vec<String>,
+}
+
+impl IgnorePattern {
+
+    /// Compiles one `.reposcanignore` line, relative to the directory it
+    /// was read from. Returns `None` for blank lines and comments.
+    fn parse( origin: &Path, line: &str ) -> Option<IgnorePattern> {
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with( '#' ) {
+            return None;
+        }
+
+        let negate = line.starts_with( '!' );
+        let pattern = if negate { &line[ 1.. ] } else { line };
+
+        let dir_only = pattern.ends_with( '/' );
+        let pattern = pattern.trim_end_matches( '/' );
+        if pattern.is_empty() {
+            return None;
+        }
+
+        let anchored = pattern.contains( '/' );
+        let pattern = pattern.strip_prefix( '/' ).unwrap_or( pattern );
+
+        let segments = pattern.split( '/' ).map( | segment | segment.to_owned() ).collect();
+
+        Some( IgnorePattern { origin: origin.to_owned(), negate, dir_only, anchored, segments } )
+    }
+
+    /// Tests whether `candidate` (an absolute path below `self.origin`)
+    /// matches this pattern, given whether it names a directory.
+    fn matches( &self, candidate: &Path, is_dir: bool ) -> bool {
+
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let Ok( relative ) = candidate.strip_prefix( &self.origin ) else {
+            return false;
+        };
+
+        if self.anchored {
+            let text_segments: Vec<&str> =
+                relative.components()
+                    .filter_map( | component | component.as_os_str().to_str() )
+                    .collect();
+            let pattern_segments: Vec<&str> =
+                self.segments.iter().map( | segment | segment.as_str() ).collect();
+            match_segments( &pattern_segments, &text_segments )
+        } else {
+            let Some( basename ) = relative.file_name().and_then( | name | name.to_str() ) else {
+                return false;
+            };
+            wildcard_match( &self.segments[ 0 ], basename )
+        }
+    }
+}
+
+/// Matches a sequence of glob segments (as produced by splitting a pattern
+/// on `/`) against a path's segments, where `**` consumes zero or more
+/// whole segments and every other segment is matched with `wildcard_match`.
+fn match_segments( pattern_segments: &[ &str ], text_segments: &[ &str ] ) -> bool {
+
+    match pattern_segments.split_first() {
+        None => text_segments.is_empty(),
+        Some( ( &"**", rest ) ) => {
+            if match_segments( rest, text_segments ) {
+                return true;
+            }
+            match text_segments.split_first() {
+                Some( ( _, text_rest ) ) => match_segments( pattern_segments, text_rest ),
+                None => false,
+            }
+        },
+        Some( ( &first, rest ) ) => {
+            match text_segments.split_first() {
+                Some( ( &text_first, text_rest ) ) =>
+                    wildcard_match( first, text_first ) && match_segments( rest, text_rest ),
+                None => false,
+            }
+        },
+    }
+}
+
+/// Matches a single path segment against a glob pattern supporting `*`
+/// (any run of characters) and `?` (any single character), as in git's
+/// wildmatch.
+fn wildcard_match( pattern: &str, text: &str ) -> bool {
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // Standard greedy/backtracking wildcard match: `star` remembers the
+    // last `*` we can fall back to and retry against more of `text`.
+    let ( mut pattern_index, mut text_index ) = ( 0, 0 );
+    let ( mut star_pattern_index, mut star_text_index ) = ( None, 0 );
+
+    while text_index < text.len() {
+        if pattern_index < pattern.len()
+            && ( pattern[ pattern_index ] == '?' || pattern[ pattern_index ] == text[ text_index ] )
+        {
+            pattern_index += 1;
+            text_index += 1;
+        } else if pattern_index < pattern.len() && pattern[ pattern_index ] == '*' {
+            star_pattern_index = Some( pattern_index );
+            star_text_index = text_index;
+            pattern_index += 1;
+        } else if let Some( star ) = star_pattern_index {
+            pattern_index = star + 1;
+            star_text_index += 1;
+            text_index = star_text_index;
+        } else {
+            return false;
+        }
+    }
+
+    while pattern_index < pattern.len() && pattern[ pattern_index ] == '*' {
+        pattern_index += 1;
+    }
+
+    pattern_index == pattern.len()
+}
+
+/// Returns whether `candidate` is excluded by any of `patterns`, applying
+/// gitignore semantics: the last pattern that matches wins, and a `!`
+/// prefix re-includes a path an earlier pattern excluded.
+fn is_ignored( patterns: &[ IgnorePattern ], candidate: &Path, is_dir: bool ) -> bool {
+
+    let mut ignored = false;
+    for pattern in patterns {
+        if pattern.matches( candidate, is_dir ) {
+            ignored = !pattern.negate;
+        }
+    }
+    ignored
+}
+
+/// How a directory was recognised as a repository, so downstream commands
+/// can open and treat it correctly.
+#[ derive( Clone, Copy, PartialEq, Eq, Debug ) ]
+pub enum RepositoryKind {
+    /// A normal working tree with a `.git` directory.
+    WorkingTree,
+    /// A linked worktree or submodule checkout, whose `.git` is a file
+    /// pointing at the real git directory elsewhere.
+    Linked,
+    /// A bare repository: just the git directory, with no working tree.
+    Bare,
+}
+
+impl RepositoryKind {
+
+    /// The suffix appended after a `\t` in `~/.reposcanconfig` for
+    /// repositories that aren't a plain working tree. A working tree is
+    /// stored as a bare path with no suffix, to keep the common case of the
+    /// file looking exactly like it used to.
+    fn storage_suffix( &self ) -> Option<&'static str> {
+        match self {
+            RepositoryKind::WorkingTree => None,
+            RepositoryKind::Linked => Some( "linked" ),
+            RepositoryKind::Bare => Some( "bare" ),
+        }
+    }
+
+    fn from_storage_suffix( suffix: Option<&str> ) -> RepositoryKind {
+        match suffix {
+            Some( "linked" ) => RepositoryKind::Linked,
+            Some( "bare" ) => RepositoryKind::Bare,
+            _ => RepositoryKind::WorkingTree,
+        }
+    }
+}
+
+/// One repository found by [`discover`], together with how it was
+/// recognised.
+pub struct DiscoveredRepository {
+    pub path: PathBuf,
+    pub kind: RepositoryKind,
+}
+
+pub fn discover( working_directory: &Path, verbose: bool ) -> Result<Vec<DiscoveredRepository>,std::io::Error> {
+    discover_with_patterns( working_directory, verbose, &[] )
+}
+
+/// Reads a `.git` *file* (as found in linked worktrees and submodules) and
+/// resolves its `gitdir:` line to confirm it really points somewhere.
+fn resolve_linked_gitdir( gitfile_path: &Path ) -> Option<PathBuf> {
+
+    let content = fs::read_to_string( gitfile_path ).ok()?;
+    let gitdir_line = content.lines().find_map( | line | line.strip_prefix( "gitdir:" ) )?;
+    let target = PathBuf::from( gitdir_line.trim() );
+
+    let resolved =
+        if target.is_absolute() {
+            target
+        } else {
+            gitfile_path.parent()?.join( target )
+        };
+
+    resolved.is_dir().then_some( resolved )
+}
+
+/// Recursive implementation of [`discover`]. `inherited_patterns` carries
+/// every `.reposcanignore` pattern compiled in an ancestor directory, so
+/// that exclusions found higher up keep applying as we descend, and any
+/// patterns found here are appended before recursing further down.
+fn discover_with_patterns(
+    working_directory: &Path,
+    verbose: bool,
+    inherited_patterns: &[ IgnorePattern ],
+) -> Result<Vec<DiscoveredRepository>,std::io::Error> {
 
     if verbose {
         println!( "scanning {working_directory:?} ..." );
     }
 
-    let mut entries: Vec<(PathBuf,String)> = Vec::new();
+    let mut entries: Vec<PathBuf> = Vec::new();
+
+    let mut local_patterns: Vec<IgnorePattern> = Vec::new();
 
-    let mut ignore_patterns: Option<BTreeSet<String>> = None;
+    let ( mut has_head_file, mut has_objects_dir, mut has_refs_dir ) = ( false, false, false );
 
     for entry in fs::read_dir( &working_directory )? {
 
@@ -29,42 +251,79 @@ pub fn discover( working_directory: &Path, verbose: bool ) -> Result<Vec<PathBuf
         if entry_path.is_dir() {
 
             if entry.eq( ".git" ) {
-                return Ok( vec![ working_directory.to_owned() ] );
-            } else {
-                entries.push( ( entry_path.clone(), entry.to_owned() ) );
+                return Ok( vec![
+                    DiscoveredRepository {
+                        path: working_directory.to_owned(),
+                        kind: RepositoryKind::WorkingTree,
+                    }
+                ] );
             }
+
+            if entry.eq( "objects" ) {
+                has_objects_dir = true;
+            }
+            if entry.eq( "refs" ) {
+                has_refs_dir = true;
+            }
+
+            entries.push( entry_path.clone() );
         }
 
-        if entry_path.is_file() && entry.eq( ".reposcanignore" ) {
-            ignore_patterns = Some(
-                fs::read_to_string( entry_path )?.lines()
-                    .map(
-                        | line |
-                        line.to_owned()
-                    ).collect()
-            )
+        if entry_path.is_file() {
+
+            if entry.eq( ".git" ) {
+                if let Some( _gitdir ) = resolve_linked_gitdir( &entry_path ) {
+                    return Ok( vec![
+                        DiscoveredRepository {
+                            path: working_directory.to_owned(),
+                            kind: RepositoryKind::Linked,
+                        }
+                    ] );
+                }
+            }
+
+            if entry.eq( "HEAD" ) {
+                has_head_file = true;
+            }
+
+            if entry.eq( ".reposcanignore" ) {
+                local_patterns = fs::read_to_string( entry_path )?.lines()
+                    .filter_map( | line | IgnorePattern::parse( working_directory, line ) )
+                    .collect();
+            }
         }
     }
 
+    // A bare repository has no `.git` directory of its own; its git
+    // directory *is* the directory we're scanning.
+    if has_head_file && has_objects_dir && has_refs_dir {
+        return Ok( vec![
+            DiscoveredRepository {
+                path: working_directory.to_owned(),
+                kind: RepositoryKind::Bare,
+            }
+        ] );
+    }
+
+    let patterns: Vec<IgnorePattern> =
+        inherited_patterns.iter().map(
+            | pattern |
+            IgnorePattern {
+                origin: pattern.origin.clone(),
+                negate: pattern.negate,
+                dir_only: pattern.dir_only,
+                anchored: pattern.anchored,
+                segments: pattern.segments.clone(),
+            }
+        )
+        .chain( local_patterns )
+        .collect();
+
     // Potentially filter entries.
-    let entries: Vec<_> = match ignore_patterns {
-        Some( ignore_patterns ) =>
-            entries.into_iter()
-                .filter_map(
-                    | ( entry_path, entry ) |
-                    if !ignore_patterns.contains( &entry ) {
-                        Some( entry_path.clone() )
-                    } else {
-                        None
-                    }
-                ).collect(),
-        None =>
-            entries.into_iter()
-                .map(
-                    | ( entry_path, _ ) |
-                    entry_path
-                ).collect(),
-    };
+    let entries: Vec<PathBuf> =
+        entries.into_iter()
+            .filter( | entry_path | !is_ignored( &patterns, entry_path, true ) )
+            .collect();
 
     let mut repositories = Vec::new();
 
@@ -72,7 +331,7 @@ pub fn discover( working_directory: &Path, verbose: bool ) -> Result<Vec<PathBuf
 
         if entry_path.is_dir() {
             repositories.append(
-                &mut discover( &entry_path, verbose )?
+                &mut discover_with_patterns( &entry_path, verbose, &patterns )?
             );
         }
     }
@@ -80,18 +339,137 @@ pub fn discover( working_directory: &Path, verbose: bool ) -> Result<Vec<PathBuf
     Ok( repositories )
 }
 
-pub fn load_known_repositories( repositories_file: &Path ) -> Result<BTreeSet<String>,std::io::Error> {
+pub fn load_known_repositories( repositories_file: &Path ) -> Result<BTreeMap<String,RepositoryKind>,std::io::Error> {
 
-    let mut repositories: BTreeSet<String> = BTreeSet::new();
+    let mut repositories: BTreeMap<String,RepositoryKind> = BTreeMap::new();
 
     let repositories_file_exists = fs::exists( repositories_file )?;
 
     if repositories_file_exists {
         let repositories_content = fs::read_to_string( repositories_file )?;
-        for repository in repositories_content.lines() {
-            repositories.insert( repository.to_owned() );
+        for line in repositories_content.lines() {
+            let mut fields = line.splitn( 2, '\t' );
+            let Some( path ) = fields.next() else {
+                continue;
+            };
+            let kind = RepositoryKind::from_storage_suffix( fields.next() );
+            repositories.insert( path.to_owned(), kind );
         }
     }
 
     Ok( repositories )
-}
\ No newline at end of file
+}
+
+/// Formats one `~/.reposcanconfig` line for `path`/`kind`, ready to be
+/// joined with a trailing newline.
+pub fn format_known_repository_line( path: &str, kind: RepositoryKind ) -> String {
+    match kind.storage_suffix() {
+        Some( suffix ) => format!( "{path}\t{suffix}" ),
+        None => path.to_owned(),
+    }
+}
+
+#[ cfg( test ) ]
+mod tests {
+
+    use super::*;
+
+    #[ test ]
+    fn wildcard_match_requires_exact_text_without_wildcards() {
+        assert!( wildcard_match( "target", "target" ) );
+        assert!( !wildcard_match( "target", "targets" ) );
+        assert!( !wildcard_match( "target", "targe" ) );
+    }
+
+    #[ test ]
+    fn wildcard_match_star_consumes_any_run_of_characters() {
+        assert!( wildcard_match( "*.rs", "main.rs" ) );
+        assert!( wildcard_match( "*.rs", ".rs" ) );
+        assert!( !wildcard_match( "*.rs", "main.rlib" ) );
+        assert!( wildcard_match( "a*b*c", "aXXbXXc" ) );
+        assert!( !wildcard_match( "a*b*c", "aXXbXX" ) );
+    }
+
+    #[ test ]
+    fn wildcard_match_question_mark_consumes_exactly_one_character() {
+        assert!( wildcard_match( "file?.txt", "file1.txt" ) );
+        assert!( !wildcard_match( "file?.txt", "file12.txt" ) );
+        assert!( !wildcard_match( "file?.txt", "file.txt" ) );
+    }
+
+    #[ test ]
+    fn match_segments_matches_plain_segments_positionally() {
+        assert!( match_segments( &[ "src", "*.rs" ], &[ "src", "main.rs" ] ) );
+        assert!( !match_segments( &[ "src", "*.rs" ], &[ "src", "sub", "main.rs" ] ) );
+        assert!( !match_segments( &[ "src", "*.rs" ], &[ "main.rs" ] ) );
+    }
+
+    #[ test ]
+    fn match_segments_double_star_consumes_zero_or_more_segments() {
+        assert!( match_segments( &[ "**", "target" ], &[ "target" ] ) );
+        assert!( match_segments( &[ "**", "target" ], &[ "a", "b", "target" ] ) );
+        assert!( match_segments( &[ "a", "**", "c" ], &[ "a", "c" ] ) );
+        assert!( match_segments( &[ "a", "**", "c" ], &[ "a", "b1", "b2", "c" ] ) );
+        assert!( !match_segments( &[ "a", "**", "c" ], &[ "a", "b", "d" ] ) );
+    }
+
+    #[ test ]
+    fn ignore_pattern_unanchored_matches_basename_anywhere() {
+        let origin = PathBuf::from( "/repo" );
+        let pattern = IgnorePattern::parse( &origin, "*.log" ).unwrap();
+
+        assert!( pattern.matches( &origin.join( "debug.log" ), false ) );
+        assert!( pattern.matches( &origin.join( "nested/debug.log" ), false ) );
+        assert!( !pattern.matches( &origin.join( "debug.txt" ), false ) );
+    }
+
+    #[ test ]
+    fn ignore_pattern_anchored_matches_only_from_its_origin() {
+        let origin = PathBuf::from( "/repo" );
+        let pattern = IgnorePattern::parse( &origin, "/build" ).unwrap();
+
+        assert!( pattern.matches( &origin.join( "build" ), true ) );
+        assert!( !pattern.matches( &origin.join( "nested/build" ), true ) );
+    }
+
+    #[ test ]
+    fn ignore_pattern_dir_only_does_not_match_files() {
+        let origin = PathBuf::from( "/repo" );
+        let pattern = IgnorePattern::parse( &origin, "build/" ).unwrap();
+
+        assert!( pattern.matches( &origin.join( "build" ), true ) );
+        assert!( !pattern.matches( &origin.join( "build" ), false ) );
+    }
+
+    #[ test ]
+    fn ignore_pattern_parse_skips_blank_lines_and_comments() {
+        let origin = PathBuf::from( "/repo" );
+        assert!( IgnorePattern::parse( &origin, "" ).is_none() );
+        assert!( IgnorePattern::parse( &origin, "   " ).is_none() );
+        assert!( IgnorePattern::parse( &origin, "# a comment" ).is_none() );
+    }
+
+    #[ test ]
+    fn is_ignored_last_match_wins_and_negation_re_includes() {
+        let origin = PathBuf::from( "/repo" );
+        let patterns = vec![
+            IgnorePattern::parse( &origin, "*.log" ).unwrap(),
+            IgnorePattern::parse( &origin, "!keep.log" ).unwrap(),
+        ];
+
+        assert!( is_ignored( &patterns, &origin.join( "debug.log" ), false ) );
+        assert!( !is_ignored( &patterns, &origin.join( "keep.log" ), false ) );
+    }
+
+    #[ test ]
+    fn is_ignored_later_pattern_can_re_exclude_after_negation() {
+        let origin = PathBuf::from( "/repo" );
+        let patterns = vec![
+            IgnorePattern::parse( &origin, "!*.log" ).unwrap(),
+            IgnorePattern::parse( &origin, "debug.log" ).unwrap(),
+        ];
+
+        assert!( !is_ignored( &patterns, &origin.join( "other.log" ), false ) );
+        assert!( is_ignored( &patterns, &origin.join( "debug.log" ), false ) );
+    }
+}